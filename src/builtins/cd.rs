@@ -2,6 +2,7 @@
 
 use super::prelude::*;
 use crate::{
+    common::escape,
     env::{EnvMode, Environment},
     fds::{wopen_dir, BEST_O_SEARCH},  // 文件描述符(file descriptions)
     path::path_apply_cdpath,  // 目录工具箱，查找返回的路径
@@ -11,6 +12,30 @@ use errno::Errno;
 use libc::{fchdir, EACCES, ELOOP, ENOENT, ENOTDIR, EPERM};
 use std::{os::fd::AsRawFd, sync::Arc};
 
+/// True if `path` (expected to already have had tilde expansion applied by the caller) has one
+/// of the shapes that can be an implicit-cd target - absolute, `./`- or `../`-prefixed, ending in
+/// `/`, or exactly `..` - and, only for those shapes, actually resolves (possibly through
+/// $CDPATH) to an existing directory. A lone `.` never counts, since that's reserved for
+/// sourcing. `pub(crate)` for the interactive auto-cd layer to call; that caller doesn't exist
+/// yet in this tree, so nothing invokes this function yet.
+#[allow(dead_code)]
+pub(crate) fn path_can_be_implicit_cd(path: &wstr, pwd: &wstr, vars: &dyn Environment) -> bool {
+    if path == L!(".") {
+        return false;
+    }
+    let looks_like_cd_path = path.starts_with(L!("/"))
+        || path.starts_with(L!("./"))
+        || path.starts_with(L!("../"))
+        || path.ends_with(L!("/"))
+        || path == L!("..");
+    if !looks_like_cd_path {
+        return false;
+    }
+    path_apply_cdpath(path, pwd, vars)
+        .into_iter()
+        .any(|dir| wopen_dir(&normalize_path(&dir, true), BEST_O_SEARCH).is_ok())
+}
+
 // The cd builtin. Changes the current directory to the one specified or to $HOME if none is
 // specified. The directory can be relative to any directory in the CDPATH variable.
 // cd 内置命令用于切换目录
@@ -25,17 +50,26 @@ pub fn cd(parser: &Parser, streams: &mut IoStreams, args: &mut [&wstr]) -> Optio
     };
 
     // 如有必要，从man手册中打印cd命令相关帮助
-    if opts.print_help { 
+    if opts.print_help {
         builtin_print_help(parser, streams, cmd);
         return STATUS_CMD_OK;
     }
 
+    // `cd DIR -- CMD...` is a scoped cd: split off everything after a literal `--` as the
+    // command to run in DIR, so it doesn't get mistaken for a second positional argument.
+    let mut positional = &args[opts.optind..];
+    let mut scoped_cmd: Option<&[&wstr]> = None;
+    if let Some(sep) = positional.iter().position(|arg| *arg == L!("--")) {
+        scoped_cmd = Some(&positional[sep + 1..]);
+        positional = &positional[..sep];
+    }
+
     let vars = parser.vars();
     let tmpstr;
 
     // 存储用户的目标路径
-    let dir_in: &wstr = if args.len() > opts.optind {
-        args[opts.optind]
+    let dir_in: &wstr = if let Some(first) = positional.first() {
+        first
     } else {
         match vars.get_unless_empty(L!("HOME")) {
             Some(v) => {  // 参数不足时，使用主目录HOME作为目标路径
@@ -65,7 +99,133 @@ pub fn cd(parser: &Parser, streams: &mut IoStreams, args: &mut [&wstr]) -> Optio
         return STATUS_CMD_ERROR;
     }
 
+    // `cd -` means "go to $OLDPWD", printing the destination, like bash. Handled natively here
+    // rather than via a `-`-to-`$OLDPWD` shell-function wrapper, so it shares the same fchdir and
+    // $PWD/$OLDPWD bookkeeping as every other cd. Resolved to a concrete path up front so
+    // `cd - -- CMD` goes through exactly the same scoped-restore handling as `cd DIR -- CMD`
+    // below, instead of a separate path that drops `-- CMD` on the floor and never restores.
+    let oldpwd_str;
+    let (target, print_destination): (&wstr, bool) = if dir_in == L!("-") {
+        match vars.get_unless_empty(L!("OLDPWD")) {
+            Some(v) => {
+                oldpwd_str = v.as_string();
+                (&oldpwd_str, true)
+            }
+            None => {
+                streams
+                    .err
+                    .append(wgettext_fmt!("%ls: OLDPWD not set\n", cmd));
+                return STATUS_CMD_ERROR;
+            }
+        }
+    } else {
+        (dir_in, false)
+    };
+
+    // The destination is announced iff `change_directory` itself succeeds, never based on a
+    // trailing scoped command's own exit status - `cd - -- false` must still print $OLDPWD even
+    // though `false` makes the overall status 1.
+    match scoped_cmd {
+        Some(scoped_cmd) if !scoped_cmd.is_empty() => {
+            run_scoped(parser, streams, cmd, target, scoped_cmd, print_destination)
+        }
+        _ => {
+            let status = change_directory(parser, streams, cmd, target);
+            if print_destination && status == STATUS_CMD_OK {
+                streams.out.appendln(target.to_owned());
+            }
+            status
+        }
+    }
+}
+
+/// Backs `cd DIR -- CMD`. Pushes the current cwd (and `$PWD`/`$OLDPWD`) onto a small stack,
+/// changes into `dir_in`, evaluates `scoped_cmd`, and on completion pops back to the saved cwd
+/// and restores `$PWD`/`$OLDPWD` to what they were before - firing the PWD variable event both
+/// times - before returning `scoped_cmd`'s own exit status. The restore runs unconditionally once
+/// we've left, regardless of how `scoped_cmd` exits. Returns early, with nothing to restore yet,
+/// if `change_directory` itself fails. `print_destination` is announced right after the directory
+/// change succeeds, not tied to `scoped_cmd`'s own exit status.
+fn run_scoped(
+    parser: &Parser,
+    streams: &mut IoStreams,
+    cmd: &wstr,
+    dir_in: &wstr,
+    scoped_cmd: &[&wstr],
+    print_destination: bool,
+) -> Option<c_int> {
+    // `parser.libdata().cwd_fd` is only populated once some `cd` has already succeeded in this
+    // session, so it can't be relied on to get us back - the very first `cd DIR -- CMD` in a
+    // fresh session would find it empty. Open our own fd to "." before changing anything, which
+    // is always valid, and restore through that instead.
+    let restore_fd = match wopen_dir(L!("."), BEST_O_SEARCH) {
+        Ok(fd) => fd,
+        Err(err) => {
+            errno::set_errno(Errno(err as i32));
+            wperror(L!("cd"));
+            streams
+                .err
+                .append(wgettext_fmt!("%ls: Could not open the current directory\n", cmd));
+            return STATUS_CMD_ERROR;
+        }
+    };
+    let prev_pwd = parser.vars().get(L!("PWD")).map(|v| v.as_string());
+    let prev_oldpwd = parser.vars().get(L!("OLDPWD")).map(|v| v.as_string());
+
+    let status = change_directory(parser, streams, cmd, dir_in);
+    if status != STATUS_CMD_OK {
+        return status;
+    }
+    if print_destination {
+        streams.out.appendln(dir_in.to_owned());
+    }
+
+    let mut cmdline = WString::new();
+    for (i, arg) in scoped_cmd.iter().enumerate() {
+        if i > 0 {
+            cmdline.push(' ');
+        }
+        cmdline.push_utfstr(&escape(arg));
+    }
+    // Use the io chain already in effect for this `cd` invocation, not a fresh empty one, so
+    // `scoped_cmd`'s output still goes through any redirection/capture the caller set up (e.g.
+    // `cd DIR -- CMD | other` or `set x (cd DIR -- CMD)`).
+    let eval_res = parser.eval(&cmdline, streams.io_chain());
+
+    // Pop back to the original directory no matter how the inner command exited.
+    unsafe { fchdir(restore_fd.as_raw_fd()) };
+    parser.libdata_mut().cwd_fd = Some(Arc::new(restore_fd));
+    match prev_pwd {
+        Some(pwd) => {
+            parser.set_var_and_fire(L!("PWD"), EnvMode::EXPORT | EnvMode::GLOBAL, vec![pwd]);
+        }
+        // $PWD wasn't set before we entered the scope - don't leave it pointing at dir_in.
+        None => {
+            parser.vars().remove(L!("PWD"), EnvMode::GLOBAL);
+        }
+    }
+    match prev_oldpwd {
+        Some(oldpwd) => {
+            parser.set_var_and_fire(L!("OLDPWD"), EnvMode::EXPORT | EnvMode::GLOBAL, vec![oldpwd]);
+        }
+        // $OLDPWD wasn't set before we entered the scope - don't leave it pointing at dir_in.
+        None => {
+            parser.vars().remove(L!("OLDPWD"), EnvMode::GLOBAL);
+        }
+    }
+
+    Some(eval_res.status.status_value())
+}
+
+/// The actual directory change: resolves `dir_in` through `$CDPATH`, `fchdir`s into the first
+/// candidate that works, and updates `$PWD`/`$OLDPWD`. This is the shared core behind every form
+/// of cd - plain, `cd -`, and the scoped `cd DIR -- CMD` - so `$OLDPWD` stays correct no matter
+/// which one ran.
+fn change_directory(parser: &Parser, streams: &mut IoStreams, cmd: &wstr, dir_in: &wstr) -> Option<c_int> {
+    let vars = parser.vars();
     let pwd = vars.get_pwd_slash();
+    // Captured before the fchdir below succeeds, so we know what to set $OLDPWD to afterwards.
+    let prev_pwd = vars.get(L!("PWD")).map(|v| v.as_string());
 
     // 调用path模块中的函数，获得用户输入目标路径的所有可能的绝对路径列表存在dirs中
     let dirs = path_apply_cdpath(dir_in, &pwd, vars);
@@ -88,9 +248,24 @@ pub fn cd(parser: &Parser, streams: &mut IoStreams, args: &mut [&wstr]) -> Optio
     let mut broken_symlink = WString::new();
     let mut broken_symlink_target = WString::new();
 
+    // What dir_in would resolve to without any $CDPATH involved, i.e. as a plain path relative to
+    // pwd (or, if absolute, dir_in itself). $CDPATH entries are user-ordered and "." need not come
+    // first (e.g. `set CDPATH /projects .`), so we can't tell a real $CDPATH jump from the plain
+    // search by position in `dirs` - only by whether a candidate resolves to this path.
+    let implicit_dir = if dir_in.starts_with(L!("/")) {
+        normalize_path(dir_in, true)
+    } else {
+        let mut joined = pwd.clone();
+        joined.push_utfstr(dir_in);
+        normalize_path(&joined, true)
+    };
+
+    // We only echo the resolved target when a candidate came from a real $CDPATH entry, matching
+    // bash - landing in pwd's own child directory is never a surprise.
     // 遍历检查dirs中所有可能的路径，寻找正确的路径
-    for dir in dirs {
+    for dir in dirs.into_iter() {
         let norm_dir = normalize_path(&dir, true);  // 将路径正常化，去除缩写或者冗余的元素
+        let used_cdpath = norm_dir != implicit_dir;
 
         errno::set_errno(Errno(0));
 
@@ -140,6 +315,14 @@ pub fn cd(parser: &Parser, streams: &mut IoStreams, args: &mut [&wstr]) -> Optio
         // Stash the fd for the cwd in the parser.
         parser.libdata_mut().cwd_fd = Some(dir_fd);
 
+        // CDPATH took us somewhere other than a child of pwd - say where, like bash does.
+        if used_cdpath {
+            streams.out.appendln(norm_dir.clone());
+        }
+
+        if let Some(prev_pwd) = prev_pwd {
+            parser.set_var_and_fire(L!("OLDPWD"), EnvMode::EXPORT | EnvMode::GLOBAL, vec![prev_pwd]);
+        }
         parser.set_var_and_fire(L!("PWD"), EnvMode::EXPORT | EnvMode::GLOBAL, vec![norm_dir]);
         return STATUS_CMD_OK;
     }
@@ -193,3 +376,62 @@ pub fn cd(parser: &Parser, streams: &mut IoStreams, args: &mut [&wstr]) -> Optio
 
     return STATUS_CMD_ERROR;
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::env::EnvStack;
+
+    #[test]
+    fn lone_dot_is_never_implicit() {
+        let vars = EnvStack::new();
+        assert!(!path_can_be_implicit_cd(L!("."), L!("/tmp/"), &vars));
+    }
+
+    #[test]
+    fn bare_word_is_not_implicit() {
+        let vars = EnvStack::new();
+        assert!(!path_can_be_implicit_cd(L!("tmp"), L!("/tmp/"), &vars));
+    }
+
+    #[test]
+    fn cdpath_miss_is_not_implicit() {
+        // Right shape (trailing "/"), but nothing on the search path resolves it.
+        let vars = EnvStack::new();
+        assert!(!path_can_be_implicit_cd(
+            L!("no_such_dir_for_this_test/"),
+            L!("/tmp/"),
+            &vars
+        ));
+    }
+
+    #[test]
+    fn absolute_path_is_implicit() {
+        let vars = EnvStack::new();
+        assert!(path_can_be_implicit_cd(L!("/tmp"), L!("/tmp/"), &vars));
+    }
+
+    #[test]
+    fn dot_slash_prefixed_path_is_implicit() {
+        let vars = EnvStack::new();
+        assert!(path_can_be_implicit_cd(L!("./"), L!("/tmp/"), &vars));
+    }
+
+    #[test]
+    fn dot_dot_slash_prefixed_path_is_implicit() {
+        let vars = EnvStack::new();
+        assert!(path_can_be_implicit_cd(L!("../"), L!("/tmp/"), &vars));
+    }
+
+    #[test]
+    fn trailing_slash_path_is_implicit() {
+        let vars = EnvStack::new();
+        assert!(path_can_be_implicit_cd(L!("tmp/"), L!("/"), &vars));
+    }
+
+    #[test]
+    fn exactly_dot_dot_is_implicit() {
+        let vars = EnvStack::new();
+        assert!(path_can_be_implicit_cd(L!(".."), L!("/tmp/"), &vars));
+    }
+}
@@ -1,8 +1,19 @@
 //! Implementation of the pwd builtin.
 use errno::errno;
+use std::ffi::{CStr, CString};
+use std::mem::MaybeUninit;
 
 use super::prelude::*;
-use crate::{env::Environment, wutil::wrealpath};
+use crate::{env::Environment, wutil::str2wcstring, wutil::wcs2zstring};
+
+const DOT: &CStr = match CStr::from_bytes_with_nul(b".\0") {
+    Ok(c) => c,
+    Err(_) => unreachable!(),
+};
+const DOTDOT: &CStr = match CStr::from_bytes_with_nul(b"..\0") {
+    Ok(c) => c,
+    Err(_) => unreachable!(),
+};
 
 // The pwd builtin. Respect -P to resolve symbolic links. Respect -L to not do that (the default).
 // 设置pwd命令选项，解析符号连接（Unix中又名软连接，类似于windows中的快速连接）还是不解析
@@ -13,6 +24,139 @@ const long_options: &[WOption] = &[
     wopt(L!("physical"), NoArgument, 'P'),
 ];
 
+/// Returns true if `pwd` no longer refers to the directory we are actually sitting in, e.g.
+/// because it (or a symlink along the way) was renamed or replaced out from under the shell.
+/// An empty or unset `$PWD` is always considered stale.
+fn pwd_is_stale(pwd: &wstr) -> bool {
+    if pwd.is_empty() {
+        return true;
+    }
+    let pwd_cstr = wcs2zstring(pwd);
+    unsafe {
+        let mut here = MaybeUninit::<libc::stat>::uninit();
+        if libc::stat(DOT.as_ptr(), here.as_mut_ptr()) != 0 {
+            return true;
+        }
+        let mut there = MaybeUninit::<libc::stat>::uninit();
+        if libc::stat(pwd_cstr.as_ptr(), there.as_mut_ptr()) != 0 {
+            return true;
+        }
+        let here = here.assume_init();
+        let there = there.assume_init();
+        here.st_dev != there.st_dev || here.st_ino != there.st_ino
+    }
+}
+
+/// Physically reconstruct the current working directory by walking up to the root, modeled on
+/// coreutils' `robust_getcwd`. At each level we stat "." to remember its dev/ino, then scan the
+/// parent directory's entries for the one whose dev/ino matches, prepend that name, and `chdir`
+/// into the parent. This never trusts `$PWD`, so it is correct even when `$PWD` has gone stale.
+/// Returns `None` (restoring the original cwd first) on any I/O error, e.g. an unreadable parent.
+fn robust_getcwd() -> Option<WString> {
+    // Keep an fd to the directory we started in so we can restore it on every exit path, success
+    // or failure alike.
+    let start_fd = unsafe { libc::open(DOT.as_ptr(), libc::O_RDONLY) };
+    if start_fd < 0 {
+        return None;
+    }
+
+    // Restores the original cwd from `start_fd` on every exit path below, success or failure.
+    macro_rules! bail {
+        () => {{
+            unsafe {
+                libc::fchdir(start_fd);
+                libc::close(start_fd);
+            }
+            return None;
+        }};
+    }
+
+    let mut components: Vec<CString> = Vec::new();
+    loop {
+        let mut here = MaybeUninit::<libc::stat>::uninit();
+        if unsafe { libc::lstat(DOT.as_ptr(), here.as_mut_ptr()) } != 0 {
+            bail!();
+        }
+        let here = unsafe { here.assume_init() };
+
+        let mut up = MaybeUninit::<libc::stat>::uninit();
+        if unsafe { libc::stat(DOTDOT.as_ptr(), up.as_mut_ptr()) } != 0 {
+            bail!();
+        }
+        let up = unsafe { up.assume_init() };
+
+        // "." and ".." share a dev+ino only at the filesystem root: we're done walking up.
+        if here.st_dev == up.st_dev && here.st_ino == up.st_ino {
+            break;
+        }
+
+        let dir = unsafe { libc::opendir(DOTDOT.as_ptr()) };
+        if dir.is_null() {
+            // Most commonly EACCES on an unreadable parent - report it rather than looping.
+            bail!();
+        }
+
+        let mut found: Option<CString> = None;
+        loop {
+            errno::set_errno(errno::Errno(0));
+            let entry = unsafe { libc::readdir(dir) };
+            if entry.is_null() {
+                break;
+            }
+            let name = unsafe { CStr::from_ptr((*entry).d_name.as_ptr()) };
+            if name.to_bytes() == b"." || name.to_bytes() == b".." {
+                continue;
+            }
+            let mut entry_stat = MaybeUninit::<libc::stat>::uninit();
+            // lstat(2) relative to the parent dir we just opened, by name.
+            let rc = unsafe {
+                libc::fstatat(
+                    libc::dirfd(dir),
+                    name.as_ptr(),
+                    entry_stat.as_mut_ptr(),
+                    libc::AT_SYMLINK_NOFOLLOW,
+                )
+            };
+            if rc != 0 {
+                continue;
+            }
+            let entry_stat = unsafe { entry_stat.assume_init() };
+            // Match against the child we remembered ("here"), crossing the mount-point boundary
+            // correctly since dev is compared too, not just ino.
+            if entry_stat.st_dev == here.st_dev && entry_stat.st_ino == here.st_ino {
+                found = Some(name.to_owned());
+                break;
+            }
+        }
+        unsafe { libc::closedir(dir) };
+
+        let name = match found {
+            Some(name) => name,
+            // The parent changed out from under us mid-scan; give up instead of spinning.
+            None => bail!(),
+        };
+        components.push(name);
+
+        if unsafe { libc::chdir(DOTDOT.as_ptr()) } != 0 {
+            bail!();
+        }
+    }
+
+    unsafe {
+        libc::fchdir(start_fd);
+        libc::close(start_fd);
+    }
+    let mut result = WString::new();
+    for component in components.iter().rev() {
+        result.push('/');
+        result.push_utfstr(&str2wcstring(component.as_bytes()));
+    }
+    if result.is_empty() {
+        result.push('/');
+    }
+    Some(result)
+}
+
 pub fn pwd(parser: &Parser, streams: &mut IoStreams, argv: &mut [&wstr]) -> Option<c_int> {
     let cmd = argv[0];
     let argc = argv.len();
@@ -42,25 +186,44 @@ pub fn pwd(parser: &Parser, streams: &mut IoStreams, argv: &mut [&wstr]) -> Opti
         return STATUS_INVALID_ARGS;
     }
 
+    if resolve_symlinks {
+        // -P always physically reconstructs the cwd; it never trusts $PWD in the first place.
+        return match robust_getcwd() {
+            Some(physical_pwd) => {
+                streams.out.appendln(physical_pwd);
+                STATUS_CMD_OK
+            }
+            None => {
+                streams.err.append(wgettext_fmt!(
+                    "%ls: realpath failed: %s\n",
+                    cmd,
+                    errno().to_string()
+                ));
+                STATUS_CMD_ERROR
+            }
+        };
+    }
+
+    // -L (the default): trust $PWD, but verify it against the real cwd first and fall back to
+    // a physical reconstruction if it has gone stale (e.g. the directory was renamed, or a
+    // symlink in the path was replaced) or is empty/unset.
     let mut pwd = WString::new();
     if let Some(tmp) = parser.vars().get(L!("PWD")) {  // 从环境中获取PWD的值，赋给pwd，但没有进行符链接解析
         pwd = tmp.as_string();
     }
-    if resolve_symlinks {  // 进行符号链接解析，获取物理路径
-        if let Some(real_pwd) = wrealpath(&pwd) {
-            pwd = real_pwd;
-        } else {
-            streams.err.append(wgettext_fmt!(
-                "%ls: realpath failed: %s\n",
-                cmd,
-                errno().to_string()
-            ));
-            return STATUS_CMD_ERROR;
+    if pwd_is_stale(&pwd) {
+        match robust_getcwd() {
+            Some(fresh) => pwd = fresh,
+            None => {
+                streams.err.append(wgettext_fmt!(
+                    "%ls: realpath failed: %s\n",
+                    cmd,
+                    errno().to_string()
+                ));
+                return STATUS_CMD_ERROR;
+            }
         }
     }
-    if pwd.is_empty() {
-        return STATUS_CMD_ERROR;
-    }
     streams.out.appendln(pwd);
     return STATUS_CMD_OK;
 }